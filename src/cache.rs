@@ -0,0 +1,102 @@
+//! Persisted manifest backing incremental builds.
+//!
+//! Rather than wiping the whole output directory on every build, the
+//! manifest remembers each output path's content hash and the hash of the
+//! input bytes it was derived from, both taken from the last run. On the
+//! next run only outputs whose content hash or input hash changed are
+//! rewritten, and
+//! outputs whose source document disappeared are deleted. The manifest
+//! itself is stored as `.smithy-cache` in the output directory, the same
+//! place a compiler might cache already-loaded sources to avoid redundant
+//! work across runs.
+
+use std::collections::{HashMap, HashSet};
+use std::collections::hash_map::DefaultHasher;
+use std::fs::File;
+use std::hash::{Hash, Hasher};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+
+use errors::SmithyError;
+
+const MANIFEST_FILE: &'static str = ".smithy-cache";
+
+/// A manifest entry: the hash of the input bytes an output was derived
+/// from, and the hash of the output bytes actually written.
+struct Entry {
+    input_hash: u64,
+    output_hash: u64,
+}
+
+pub struct Manifest {
+    entries: HashMap<PathBuf, Entry>,
+}
+
+impl Manifest {
+    /// Load the manifest from `output_path`, or an empty one if none exists yet.
+    pub fn load(output_path: &Path) -> Result<Manifest, SmithyError> {
+        let manifest_path = output_path.join(MANIFEST_FILE);
+        let mut entries = HashMap::new();
+
+        if manifest_path.exists() {
+            let file = File::open(&manifest_path)?;
+            for line in BufReader::new(file).lines() {
+                let line = line?;
+                let fields: Vec<&str> = line.splitn(3, '\t').collect();
+                if fields.len() == 3 {
+                    if let (Ok(input_hash), Ok(output_hash)) = (fields[1].parse(), fields[2].parse()) {
+                        entries.insert(PathBuf::from(fields[0]), Entry { input_hash: input_hash, output_hash: output_hash });
+                    }
+                }
+            }
+        }
+
+        Ok(Manifest { entries: entries })
+    }
+
+    /// Persist the manifest back to `output_path`.
+    pub fn save(&self, output_path: &Path) -> Result<(), SmithyError> {
+        let mut file = File::create(output_path.join(MANIFEST_FILE))?;
+        for (path, entry) in self.entries.iter() {
+            writeln!(file, "{}\t{}\t{}", path.display(), entry.input_hash, entry.output_hash)?;
+        }
+        Ok(())
+    }
+
+    /// Hash a document's bytes for comparison against the manifest, whether
+    /// they are the input bytes a document was loaded from or the final
+    /// output bytes it was rendered to.
+    pub fn hash(bytes: &[u8]) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        bytes.hash(&mut hasher);
+        hasher.finish()
+    }
+
+    /// Whether `path` is missing from the manifest or its recorded input or
+    /// output hash differs from the ones given.
+    pub fn is_stale(&self, path: &Path, input_hash: u64, output_hash: u64) -> bool {
+        match self.entries.get(path) {
+            Some(entry) => entry.input_hash != input_hash || entry.output_hash != output_hash,
+            None => true,
+        }
+    }
+
+    /// Record that `path` was derived from input bytes hashing to
+    /// `input_hash` and written with content hashing to `output_hash`.
+    pub fn record(&mut self, path: PathBuf, input_hash: u64, output_hash: u64) {
+        self.entries.insert(path, Entry { input_hash: input_hash, output_hash: output_hash });
+    }
+
+    /// Drop and return every recorded path whose source no longer appears in `seen`.
+    pub fn remove_missing(&mut self, seen: &HashSet<PathBuf>) -> Vec<PathBuf> {
+        let missing: Vec<PathBuf> = self.entries.keys()
+            .filter(|path| !seen.contains(*path))
+            .cloned()
+            .collect();
+
+        for path in &missing {
+            self.entries.remove(path);
+        }
+        missing
+    }
+}
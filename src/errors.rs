@@ -1,7 +1,7 @@
 use std::io;
 use std::error::{Error};
 use std::fmt::{self, Display, Formatter};
-use std::path::StripPrefixError;
+use std::path::{Path, StripPrefixError};
 
 use walkdir::Error as WalkDirError;
 
@@ -18,6 +18,10 @@ impl SmithyError {
             cause: cause,
         }
     }
+
+    pub fn circular_import<P: AsRef<Path>>(path: P) -> Self {
+        SmithyError::new(format!("Circular import: {} transitively includes itself", path.as_ref().display()), None)
+    }
 }
 
 impl Display for SmithyError {
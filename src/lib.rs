@@ -1,31 +1,45 @@
 #![feature(question_mark)]
+#[cfg(feature = "testing")]
+extern crate regex;
+#[cfg(any(test, feature = "testing"))]
 extern crate tempdir;
 extern crate walkdir;
 extern crate yaml_rust;
 
+mod cache;
 mod errors;
+mod loader;
+mod templates;
+#[cfg(feature = "testing")]
+#[macro_use]
+pub mod testing;
 
+use std::collections::{HashMap, HashSet};
 use std::path::PathBuf;
 use std::fs::{self, File};
-use std::io::{Read, Write};
+use std::io::Write;
 
-use walkdir::{WalkDir};
 use yaml_rust::{Yaml, YamlLoader};
 
 pub use errors::SmithyError;
+pub use loader::{FsLoader, Loader, MemoryLoader};
 
 
 pub struct Smithy<'a> {
-    input_path: PathBuf,
     output_path: PathBuf,
+    templates_path: PathBuf,
+    incremental: bool,
+    sources: Vec<Box<Loader + 'a>>,
     plugins: Vec<Box<SmithyPlugin + 'a>>,
 }
 
 impl<'a> Smithy<'a> {
     pub fn builder<P: Into<PathBuf>>(input_path: P, output_path: P) -> Smithy<'a> {
         Smithy {
-            input_path: input_path.into(),
             output_path: output_path.into(),
+            templates_path: PathBuf::from("templates"),
+            incremental: false,
+            sources: vec![Box::new(FsLoader::new(input_path))],
             plugins: vec![],
         }
     }
@@ -35,27 +49,64 @@ impl<'a> Smithy<'a> {
         self
     }
 
+    /// Add another source of input documents. Sources are loaded in the
+    /// order they were added; if two sources produce the same path, the
+    /// later one wins.
+    pub fn add_source<T: Loader + 'a>(mut self, loader: T) -> Self {
+        self.sources.push(Box::new(loader));
+        self
+    }
+
+    pub fn templates_path<P: Into<PathBuf>>(mut self, templates_path: P) -> Self {
+        self.templates_path = templates_path.into();
+        self
+    }
+
+    /// When enabled, `build` keeps a manifest of each output's content hash
+    /// in the output directory and only rewrites outputs whose content or
+    /// input bytes changed, instead of wiping and regenerating the whole
+    /// directory.
+    pub fn incremental(mut self, incremental: bool) -> Self {
+        self.incremental = incremental;
+        self
+    }
+
     pub fn build(&mut self) -> Result<(), SmithyError> {
-        let mut documents = vec![];
-        for entry in WalkDir::new(&self.input_path) {
-            let entry = entry?;
-            if entry.file_type().is_dir() {
-                continue;
+        let mut order = vec![];
+        let mut by_path: HashMap<PathBuf, Vec<u8>> = HashMap::new();
+
+        for source in self.sources.iter() {
+            for (path, bytes) in source.load()? {
+                if !by_path.contains_key(&path) {
+                    order.push(path.clone());
+                }
+                by_path.insert(path, bytes);
             }
-            println!("processing {:?}", entry.path());
-            let mut input_file = File::open(entry.path())?;
-            let mut file_contents = String::new();
-            input_file.read_to_string(&mut file_contents)?;
-
-            let rel_path = entry.path().strip_prefix(&self.input_path)?;
-            let doc = Document::from_str(rel_path, &file_contents);
-            documents.push(doc);
         }
 
+        let mut documents = Vec::with_capacity(order.len());
+        let mut input_hashes: HashMap<PathBuf, u64> = HashMap::with_capacity(order.len());
+        for path in order {
+            let bytes = by_path.remove(&path).expect("path was just recorded in order");
+            println!("processing {:?}", path);
+            input_hashes.insert(path.clone(), cache::Manifest::hash(&bytes));
+            documents.push(Document::from_bytes(path, bytes));
+        }
+
+        documents = templates::resolve(documents, &self.templates_path)?;
+
         for plugin in self.plugins.iter() {
             documents = plugin.process(documents)?;
         }
 
+        if self.incremental {
+            self.write_incremental(documents, &input_hashes)
+        } else {
+            self.write_clean(documents)
+        }
+    }
+
+    fn write_clean(&self, documents: Vec<Document>) -> Result<(), SmithyError> {
         fs::remove_dir_all(&self.output_path)?;
 
         for doc in documents {
@@ -64,10 +115,48 @@ impl<'a> Smithy<'a> {
                 fs::create_dir_all(parent)?;
             }
             let mut output_file = File::create(output_file_path)?;
-            output_file.write(doc.body.as_bytes())?;
+            match doc.body {
+                Body::Text(text) => output_file.write(text.as_bytes())?,
+                Body::Bytes(bytes) => output_file.write(&bytes)?,
+            };
         }
         Ok(())
     }
+
+    fn write_incremental(&self, documents: Vec<Document>, input_hashes: &HashMap<PathBuf, u64>) -> Result<(), SmithyError> {
+        fs::create_dir_all(&self.output_path)?;
+        let mut manifest = cache::Manifest::load(&self.output_path)?;
+        let mut seen = HashSet::new();
+
+        for doc in documents {
+            // A plugin may have renamed `doc.path` away from the input path
+            // it was loaded from; fall back to 0, so a renamed output is
+            // compared against the manifest on its output hash alone.
+            let input_hash = input_hashes.get(&doc.path).cloned().unwrap_or(0);
+            let bytes = match doc.body {
+                Body::Text(text) => text.into_bytes(),
+                Body::Bytes(bytes) => bytes,
+            };
+            let output_hash = cache::Manifest::hash(&bytes);
+            seen.insert(doc.path.clone());
+
+            if manifest.is_stale(&doc.path, input_hash, output_hash) {
+                let output_file_path = self.output_path.join(&doc.path);
+                if let Some(parent) = output_file_path.parent() {
+                    fs::create_dir_all(parent)?;
+                }
+                let mut output_file = File::create(output_file_path)?;
+                output_file.write(&bytes)?;
+                manifest.record(doc.path, input_hash, output_hash);
+            }
+        }
+
+        for stale_path in manifest.remove_missing(&seen) {
+            let _ = fs::remove_file(self.output_path.join(stale_path));
+        }
+
+        manifest.save(&self.output_path)
+    }
 }
 
 pub trait SmithyPlugin {
@@ -84,13 +173,51 @@ pub trait SmithyPlugin {
     }
 }
 
+/// A document's contents, either decoded text or raw, non-UTF8 bytes.
+///
+/// Plugins should check the variant before mutating `body`: only `Text`
+/// documents have front matter and are meaningful to transform as text.
+#[derive(Debug, PartialEq)]
+pub enum Body {
+    Text(String),
+    Bytes(Vec<u8>),
+}
+
+impl Body {
+    pub fn is_text(&self) -> bool {
+        match *self {
+            Body::Text(_) => true,
+            Body::Bytes(_) => false,
+        }
+    }
+
+    pub fn as_text(&self) -> Option<&str> {
+        match *self {
+            Body::Text(ref text) => Some(text),
+            Body::Bytes(_) => None,
+        }
+    }
+}
+
 pub struct Document {
     pub metadata: Yaml,
-    pub body: String,
+    pub body: Body,
     pub path: PathBuf,
 }
 
 impl Document {
+    /// Parse a document from raw bytes, decoding it and splitting off its
+    /// front matter when the bytes are valid UTF-8. Non-UTF8 input (images,
+    /// fonts, and other binary assets) is kept as-is, with `Yaml::Null`
+    /// metadata, so it can pass through the same pipeline untouched.
+    pub fn from_bytes<T: Into<PathBuf>>(path: T, bytes: Vec<u8>) -> Document {
+        let path = path.into();
+        match String::from_utf8(bytes) {
+            Ok(text) => Document::from_str(path, &text),
+            Err(err) => Document { metadata: Yaml::Null, body: Body::Bytes(err.into_bytes()), path: path },
+        }
+    }
+
     pub fn from_str<T: Into<PathBuf>>(path: T, text: &str) -> Document {
         let path = path.into();
         let splits: Vec<&str> = text.split("---\n").collect();
@@ -99,9 +226,9 @@ impl Document {
             let front_matter_text = splits[1];
             let body = splits[2..].join("---\n").trim().to_string() + "\n";
             let front_matter = YamlLoader::load_from_str(front_matter_text).unwrap()[0].clone();
-            Document { metadata: front_matter, body: body, path: path }
+            Document { metadata: front_matter, body: Body::Text(body), path: path }
         } else {
-            Document { metadata: Yaml::Null, body: text.to_string(), path: path }
+            Document { metadata: Yaml::Null, body: Body::Text(text.to_string()), path: path }
         }
     }
 }
@@ -115,14 +242,14 @@ mod tests {
     use yaml_rust::Yaml;
     use tempdir::TempDir;
 
-    use super::{Document, Smithy, SmithyError, SmithyPlugin};
+    use super::{Body, Document, MemoryLoader, Smithy, SmithyError, SmithyPlugin};
 
     #[test]
     fn parse_doc_no_frontmatter() {
         let doc = "Some body";
         let parsed = Document::from_str("doc.txt", doc);
         assert_eq!(parsed.metadata, Yaml::Null);
-        assert_eq!(parsed.body, "Some body");
+        assert_eq!(parsed.body, Body::Text("Some body".to_string()));
     }
 
     #[test]
@@ -142,7 +269,7 @@ mod tests {
             },
         };
         assert_eq!(parsed.metadata["title"], Yaml::String("Some doc".to_string()));
-        assert_eq!(parsed.body, "This is the body of the document.\n");
+        assert_eq!(parsed.body, Body::Text("This is the body of the document.\n".to_string()));
     }
 
     #[test]
@@ -150,7 +277,15 @@ mod tests {
         let doc = "---\nThis is the body of the document.";
         let parsed = Document::from_str("doc.txt", doc);
         assert_eq!(parsed.metadata, Yaml::Null);
-        assert_eq!(parsed.body, "---\nThis is the body of the document.");
+        assert_eq!(parsed.body, Body::Text("---\nThis is the body of the document.".to_string()));
+    }
+
+    #[test]
+    fn parse_doc_non_utf8_passes_through_as_bytes() {
+        let bytes = vec![0xff, 0xfe, 0x00, 0x01];
+        let parsed = Document::from_bytes("image.png", bytes.clone());
+        assert_eq!(parsed.metadata, Yaml::Null);
+        assert_eq!(parsed.body, Body::Bytes(bytes));
     }
 
     #[test]
@@ -180,7 +315,9 @@ mod tests {
         impl SmithyPlugin for ShoutingPlugin {
             fn process(&self, files: Vec<Document>) -> Result<Vec<Document>, SmithyError> {
                 Ok(files.into_iter().map(|mut file| {
-                    file.body = file.body.to_uppercase();
+                    if let Body::Text(text) = file.body {
+                        file.body = Body::Text(text.to_uppercase());
+                    }
                     file
                 }).collect())
             }
@@ -209,7 +346,9 @@ mod tests {
         impl SmithyPlugin for ShoutingPlugin {
             fn process_file(&self, file: Document) -> Result<Document, SmithyError> {
                 let mut file = file;
-                file.body = file.body.to_uppercase();
+                if let Body::Text(text) = file.body {
+                    file.body = Body::Text(text.to_uppercase());
+                }
                 Ok(file)
             }
         }
@@ -289,4 +428,251 @@ mod tests {
         let output_foo_file_path = output_dir.path().join("foo.txt");
         assert!(!output_foo_file_path.exists());
     }
+
+    #[test]
+    fn test_binary_file_passthrough() {
+        let input_dir = TempDir::new("input").unwrap();
+        let output_dir = TempDir::new("output").unwrap();
+
+        let image_bytes: Vec<u8> = vec![0x89, 0x50, 0x4e, 0x47, 0x0d, 0x0a, 0x1a, 0x0a, 0xff];
+        let mut input_doc = File::create(input_dir.path().join("image.png")).unwrap();
+        input_doc.write(&image_bytes).unwrap();
+
+        Smithy::builder(input_dir.path(), output_dir.path()).build().unwrap();
+
+        let mut output_doc = File::open(output_dir.path().join("image.png")).unwrap();
+        let mut buf = vec![];
+        output_doc.read_to_end(&mut buf).unwrap();
+        assert_eq!(buf, image_bytes);
+    }
+
+    #[test]
+    fn test_incremental_build_leaves_unrelated_files() {
+        let input_dir = TempDir::new("input").unwrap();
+        let output_dir = TempDir::new("output").unwrap();
+        let mut input_doc = File::create(input_dir.path().join("doc.txt")).unwrap();
+        input_doc.write("Document body".as_bytes()).unwrap();
+
+        let unrelated_file_path = output_dir.path().join("unrelated.txt");
+        File::create(&unrelated_file_path).unwrap();
+
+        Smithy::builder(input_dir.path(), output_dir.path())
+            .incremental(true)
+            .build()
+            .unwrap();
+
+        assert!(unrelated_file_path.exists());
+
+        let mut output_doc = File::open(output_dir.path().join("doc.txt")).unwrap();
+        let mut buf = String::new();
+        output_doc.read_to_string(&mut buf).unwrap();
+        assert_eq!(buf, "Document body");
+    }
+
+    #[test]
+    fn test_incremental_build_removes_output_for_deleted_source() {
+        let input_dir = TempDir::new("input").unwrap();
+        let output_dir = TempDir::new("output").unwrap();
+        let doc_path = input_dir.path().join("doc.txt");
+        File::create(&doc_path).unwrap().write("Document body".as_bytes()).unwrap();
+
+        Smithy::builder(input_dir.path(), output_dir.path())
+            .incremental(true)
+            .build()
+            .unwrap();
+        assert!(output_dir.path().join("doc.txt").exists());
+
+        fs::remove_file(&doc_path).unwrap();
+        Smithy::builder(input_dir.path(), output_dir.path())
+            .incremental(true)
+            .build()
+            .unwrap();
+
+        assert!(!output_dir.path().join("doc.txt").exists());
+    }
+
+    #[test]
+    fn test_incremental_build_does_not_rewrite_unchanged_output() {
+        let input_dir = TempDir::new("input").unwrap();
+        let output_dir = TempDir::new("output").unwrap();
+        File::create(input_dir.path().join("doc.txt")).unwrap().write("Document body".as_bytes()).unwrap();
+
+        Smithy::builder(input_dir.path(), output_dir.path())
+            .incremental(true)
+            .build()
+            .unwrap();
+        let output_file_path = output_dir.path().join("doc.txt");
+        let first_mtime = fs::metadata(&output_file_path).unwrap().modified().unwrap();
+
+        Smithy::builder(input_dir.path(), output_dir.path())
+            .incremental(true)
+            .build()
+            .unwrap();
+        let second_mtime = fs::metadata(&output_file_path).unwrap().modified().unwrap();
+
+        assert_eq!(first_mtime, second_mtime, "unchanged output should not have been rewritten");
+    }
+
+    #[test]
+    #[cfg(feature = "testing")]
+    fn test_testing_harness_checks_exact_and_matching_output() {
+        struct ShoutingPlugin;
+
+        impl SmithyPlugin for ShoutingPlugin {
+            fn process_file(&self, file: Document) -> Result<Document, SmithyError> {
+                let mut file = file;
+                if let Body::Text(text) = file.body {
+                    file.body = Body::Text(text.to_uppercase());
+                }
+                Ok(file)
+            }
+        }
+
+        ::testing::Test::new()
+            .input("doc.txt", "---\ntitle: Foo\n---\n\nDocument body")
+            .plugin(ShoutingPlugin)
+            .expect_output("doc.txt", "DOCUMENT BODY\n")
+            .expect_output_matching("doc.txt", "^DOCUMENT")
+            .run();
+    }
+
+    #[cfg(feature = "testing")]
+    smithy_test! {
+        name: test_smithy_test_macro_checks_output,
+        inputs: { "doc.txt" => "---\ntitle: Foo\n---\n\nDocument body" },
+        plugins: [ ],
+        outputs: { "doc.txt" => "Document body\n" },
+    }
+
+    #[cfg(feature = "testing")]
+    smithy_test! {
+        name: test_smithy_test_macro_checks_matching_output,
+        inputs: { "doc.txt" => "---\ntitle: Foo\n---\n\nDocument body" },
+        plugins: [ ],
+        outputs: { "doc.txt" => "Document body\n" },
+        outputs_matching: { "doc.txt" => "^Document" },
+    }
+
+    #[test]
+    #[cfg(feature = "testing")]
+    fn test_testing_harness_registers_templates_outside_input_tree() {
+        ::testing::Test::new()
+            .input("doc.txt", "---\ntitle: Foo\nlayout: base.html\n---\n\nDocument body")
+            .template("base.html", "Header\n{{ content }}\nFooter\n")
+            .expect_output("doc.txt", "Header\nDocument body\n\nFooter\n")
+            .run();
+    }
+
+    #[test]
+    fn test_include_expands_partial_contents() {
+        let input_dir = TempDir::new("input").unwrap();
+        let output_dir = TempDir::new("output").unwrap();
+        let templates_dir = TempDir::new("templates").unwrap();
+
+        File::create(input_dir.path().join("doc.txt")).unwrap()
+            .write("---\ntitle: Foo\n---\n\nBefore {% include \"nav.html\" %} After".as_bytes()).unwrap();
+        File::create(templates_dir.path().join("nav.html")).unwrap()
+            .write("Nav".as_bytes()).unwrap();
+
+        Smithy::builder(input_dir.path(), output_dir.path())
+            .templates_path(templates_dir.path())
+            .build()
+            .unwrap();
+
+        let mut output_doc = File::open(output_dir.path().join("doc.txt")).unwrap();
+        let mut buf = String::new();
+        output_doc.read_to_string(&mut buf).unwrap();
+        assert_eq!(buf, "Before Nav After\n");
+    }
+
+    #[test]
+    fn test_diamond_includes_are_not_flagged_circular() {
+        let input_dir = TempDir::new("input").unwrap();
+        let output_dir = TempDir::new("output").unwrap();
+        let templates_dir = TempDir::new("templates").unwrap();
+
+        // a includes both b and c, and b and c both include d. d is reachable
+        // by two separate paths, which must not be mistaken for a cycle.
+        File::create(input_dir.path().join("doc.txt")).unwrap()
+            .write("---\ntitle: Foo\n---\n\n{% include \"a.html\" %}".as_bytes()).unwrap();
+        File::create(templates_dir.path().join("a.html")).unwrap()
+            .write("{% include \"b.html\" %}-{% include \"c.html\" %}".as_bytes()).unwrap();
+        File::create(templates_dir.path().join("b.html")).unwrap()
+            .write("b{% include \"d.html\" %}".as_bytes()).unwrap();
+        File::create(templates_dir.path().join("c.html")).unwrap()
+            .write("c{% include \"d.html\" %}".as_bytes()).unwrap();
+        File::create(templates_dir.path().join("d.html")).unwrap()
+            .write("d".as_bytes()).unwrap();
+
+        Smithy::builder(input_dir.path(), output_dir.path())
+            .templates_path(templates_dir.path())
+            .build()
+            .unwrap();
+
+        let mut output_doc = File::open(output_dir.path().join("doc.txt")).unwrap();
+        let mut buf = String::new();
+        output_doc.read_to_string(&mut buf).unwrap();
+        assert_eq!(buf, "bd-cd\n");
+    }
+
+    #[test]
+    fn test_circular_include_returns_error_naming_the_path() {
+        let input_dir = TempDir::new("input").unwrap();
+        let output_dir = TempDir::new("output").unwrap();
+        let templates_dir = TempDir::new("templates").unwrap();
+
+        // a includes b, and b includes a back: a cycle that isn't a direct
+        // self-include, so the chain tracking has to catch it transitively.
+        File::create(input_dir.path().join("doc.txt")).unwrap()
+            .write("---\ntitle: Foo\n---\n\n{% include \"a.html\" %}".as_bytes()).unwrap();
+        File::create(templates_dir.path().join("a.html")).unwrap()
+            .write("{% include \"b.html\" %}".as_bytes()).unwrap();
+        File::create(templates_dir.path().join("b.html")).unwrap()
+            .write("{% include \"a.html\" %}".as_bytes()).unwrap();
+
+        let err = Smithy::builder(input_dir.path(), output_dir.path())
+            .templates_path(templates_dir.path())
+            .build()
+            .unwrap_err();
+
+        assert!(format!("{}", err).contains("a.html"), "error did not name the offending path: {}", err);
+    }
+
+    #[test]
+    fn test_memory_loader_generates_documents() {
+        let input_dir = TempDir::new("input").unwrap();
+        let output_dir = TempDir::new("output").unwrap();
+
+        let sitemap = MemoryLoader::new().add("sitemap.txt", "generated content".as_bytes().to_vec());
+
+        Smithy::builder(input_dir.path(), output_dir.path())
+            .add_source(sitemap)
+            .build()
+            .unwrap();
+
+        let mut output_doc = File::open(output_dir.path().join("sitemap.txt")).unwrap();
+        let mut buf = String::new();
+        output_doc.read_to_string(&mut buf).unwrap();
+        assert_eq!(buf, "generated content");
+    }
+
+    #[test]
+    fn test_later_source_overrides_earlier_on_collision() {
+        let input_dir = TempDir::new("input").unwrap();
+        let output_dir = TempDir::new("output").unwrap();
+        let mut input_doc = File::create(input_dir.path().join("doc.txt")).unwrap();
+        input_doc.write("Original body".as_bytes()).unwrap();
+
+        let overrides = MemoryLoader::new().add("doc.txt", "Overridden body".as_bytes().to_vec());
+
+        Smithy::builder(input_dir.path(), output_dir.path())
+            .add_source(overrides)
+            .build()
+            .unwrap();
+
+        let mut output_doc = File::open(output_dir.path().join("doc.txt")).unwrap();
+        let mut buf = String::new();
+        output_doc.read_to_string(&mut buf).unwrap();
+        assert_eq!(buf, "Overridden body");
+    }
 }
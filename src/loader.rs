@@ -0,0 +1,76 @@
+//! Pluggable sources of input documents.
+//!
+//! `build` used to be hard-wired to a single directory walked with
+//! `WalkDir`. A `Loader` abstracts that away: it just hands back the raw
+//! bytes for every path it knows about, so a site can source content from
+//! a filesystem tree, generate it in memory, or (via `Smithy::add_source`)
+//! combine several of these, with later sources overriding earlier ones on
+//! a path collision.
+
+use std::fs::File;
+use std::io::Read;
+use std::path::PathBuf;
+
+use walkdir::WalkDir;
+
+use errors::SmithyError;
+
+pub trait Loader {
+    fn load(&self) -> Result<Vec<(PathBuf, Vec<u8>)>, SmithyError>;
+}
+
+/// Loads every file under `root`, yielding paths relative to it. This is
+/// the loader `Smithy::builder` installs for its `input_path` argument.
+pub struct FsLoader {
+    root: PathBuf,
+}
+
+impl FsLoader {
+    pub fn new<P: Into<PathBuf>>(root: P) -> FsLoader {
+        FsLoader { root: root.into() }
+    }
+}
+
+impl Loader for FsLoader {
+    fn load(&self) -> Result<Vec<(PathBuf, Vec<u8>)>, SmithyError> {
+        let mut entries = vec![];
+        for entry in WalkDir::new(&self.root) {
+            let entry = entry?;
+            if entry.file_type().is_dir() {
+                continue;
+            }
+
+            let mut file = File::open(entry.path())?;
+            let mut bytes = vec![];
+            file.read_to_end(&mut bytes)?;
+
+            let rel_path = entry.path().strip_prefix(&self.root)?.to_path_buf();
+            entries.push((rel_path, bytes));
+        }
+        Ok(entries)
+    }
+}
+
+/// Holds documents supplied programmatically instead of read from disk,
+/// for generated content or for tests that don't want to touch the
+/// filesystem at all.
+pub struct MemoryLoader {
+    entries: Vec<(PathBuf, Vec<u8>)>,
+}
+
+impl MemoryLoader {
+    pub fn new() -> MemoryLoader {
+        MemoryLoader { entries: vec![] }
+    }
+
+    pub fn add<P: Into<PathBuf>, B: Into<Vec<u8>>>(mut self, path: P, body: B) -> Self {
+        self.entries.push((path.into(), body.into()));
+        self
+    }
+}
+
+impl Loader for MemoryLoader {
+    fn load(&self) -> Result<Vec<(PathBuf, Vec<u8>)>, SmithyError> {
+        Ok(self.entries.clone())
+    }
+}
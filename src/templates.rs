@@ -0,0 +1,152 @@
+//! Layout and partial inclusion for documents.
+//!
+//! A document can name a `layout:` template in its front matter and/or pull
+//! in shared snippets with inline `{% include "path" %}` directives. This
+//! module expands both before documents reach the plugin pipeline.
+//!
+//! Templates live under a configurable `templates/` directory and are
+//! expanded recursively; there is no separate worklist, the call stack is
+//! the dependency stack. While a template's own includes and layout are
+//! being expanded, its path sits on a chain of the templates in progress,
+//! so a template that transitively includes itself is caught via
+//! `SmithyError::circular_import` instead of recursing forever. Loaded
+//! sources and fully-expanded templates are cached, so a layout shared by
+//! many pages is read and expanded only once.
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+use yaml_rust::Yaml;
+
+use errors::SmithyError;
+use {Body, Document};
+
+const INCLUDE_OPEN: &'static str = "{% include \"";
+const INCLUDE_CLOSE: &'static str = "\" %}";
+const CONTENT_MARKER: &'static str = "{{ content }}";
+
+/// Resolve `layout:` and `{% include %}` directives for every document.
+///
+/// `templates_path` is the directory layouts and partials are loaded from.
+pub fn resolve(documents: Vec<Document>, templates_path: &Path) -> Result<Vec<Document>, SmithyError> {
+    let mut source_cache: HashMap<PathBuf, String> = HashMap::new();
+    let mut resolved_cache: HashMap<PathBuf, String> = HashMap::new();
+
+    let mut resolved_documents = Vec::with_capacity(documents.len());
+    for doc in documents {
+        let text = match doc.body {
+            Body::Text(text) => text,
+            Body::Bytes(bytes) => {
+                resolved_documents.push(Document { metadata: doc.metadata, body: Body::Bytes(bytes), path: doc.path });
+                continue;
+            }
+        };
+
+        let mut chain = vec![];
+        let mut body = expand_includes(&text, templates_path, &mut source_cache, &mut resolved_cache, &mut chain)?;
+
+        if let Some(layout_path) = layout_of(&doc.metadata) {
+            let layout = resolve_template(&layout_path, templates_path, &mut source_cache, &mut resolved_cache, &mut chain)?;
+            body = layout.replacen(CONTENT_MARKER, &body, 1);
+        }
+
+        resolved_documents.push(Document { metadata: doc.metadata, body: Body::Text(body), path: doc.path });
+    }
+    Ok(resolved_documents)
+}
+
+/// Fully expand a single template (its own includes, then its own layout if
+/// it declares one), tracking `chain` to detect circular imports.
+fn resolve_template(path: &Path,
+                     templates_path: &Path,
+                     source_cache: &mut HashMap<PathBuf, String>,
+                     resolved_cache: &mut HashMap<PathBuf, String>,
+                     chain: &mut Vec<PathBuf>)
+                     -> Result<String, SmithyError> {
+    let full_path = templates_path.join(path);
+
+    if let Some(cached) = resolved_cache.get(&full_path) {
+        return Ok(cached.clone());
+    }
+
+    if chain.contains(&full_path) {
+        return Err(SmithyError::circular_import(&full_path));
+    }
+
+    let source = load_source(&full_path, source_cache)?;
+    let parsed = Document::from_str(full_path.clone(), &source);
+    let text = parsed.body.as_text().expect("Document::from_str always produces Body::Text").to_string();
+
+    chain.push(full_path.clone());
+    let mut body = expand_includes(&text, templates_path, source_cache, resolved_cache, chain)?;
+
+    if let Some(layout_path) = layout_of(&parsed.metadata) {
+        let layout = resolve_template(&layout_path, templates_path, source_cache, resolved_cache, chain)?;
+        body = layout.replacen(CONTENT_MARKER, &body, 1);
+    }
+    chain.pop();
+
+    resolved_cache.insert(full_path, body.clone());
+    Ok(body)
+}
+
+/// Replace every `{% include "path" %}` directive in `body` with the fully
+/// expanded contents of the named template.
+fn expand_includes(body: &str,
+                    templates_path: &Path,
+                    source_cache: &mut HashMap<PathBuf, String>,
+                    resolved_cache: &mut HashMap<PathBuf, String>,
+                    chain: &mut Vec<PathBuf>)
+                    -> Result<String, SmithyError> {
+    let mut result = String::with_capacity(body.len());
+    let mut rest = body;
+
+    while let Some(start) = rest.find(INCLUDE_OPEN) {
+        result.push_str(&rest[..start]);
+
+        let after_open = &rest[start + INCLUDE_OPEN.len()..];
+        let end = match after_open.find(INCLUDE_CLOSE) {
+            Some(end) => end,
+            None => {
+                result.push_str(&rest[start..]);
+                rest = "";
+                break;
+            }
+        };
+
+        let include_path = PathBuf::from(&after_open[..end]);
+        let included = resolve_template(&include_path, templates_path, source_cache, resolved_cache, chain)?;
+        result.push_str(&included);
+
+        rest = &after_open[end + INCLUDE_CLOSE.len()..];
+    }
+    result.push_str(rest);
+
+    Ok(result)
+}
+
+fn load_source(path: &Path, source_cache: &mut HashMap<PathBuf, String>) -> Result<String, SmithyError> {
+    if let Some(cached) = source_cache.get(path) {
+        return Ok(cached.clone());
+    }
+
+    let mut file = File::open(path)?;
+    let mut contents = String::new();
+    file.read_to_string(&mut contents)?;
+
+    source_cache.insert(path.to_path_buf(), contents.clone());
+    Ok(contents)
+}
+
+fn layout_of(metadata: &Yaml) -> Option<PathBuf> {
+    match metadata {
+        &Yaml::Hash(ref hash) => {
+            hash.get(&Yaml::String("layout".to_string()))
+                .and_then(|layout| layout.as_str())
+                .map(PathBuf::from)
+        }
+        _ => None,
+    }
+}
@@ -0,0 +1,159 @@
+//! In-memory test harness for plugin authors.
+//!
+//! Exercising a plugin has always meant hand-rolling two `TempDir`s,
+//! writing fixture files, running `Smithy::builder(...).build()`, and
+//! reading the output back — boilerplate every downstream crate ends up
+//! reinventing. `Test` wraps that in a fluent builder, and `smithy_test!`
+//! wraps `Test` in a `#[test]` fn for table-driven suites.
+//!
+//! Gated behind the `testing` feature so the `regex` and `tempdir` crates
+//! stay out of the dependency tree of consumers who never write plugin tests.
+
+use std::fs::{self, File};
+use std::io::{Read, Write};
+use std::path::PathBuf;
+
+use regex::Regex;
+use tempdir::TempDir;
+
+use {FsLoader, Smithy, SmithyPlugin};
+
+enum Expectation {
+    Exact(String),
+    Matching(String),
+}
+
+pub struct Test<'a> {
+    inputs: Vec<(PathBuf, String)>,
+    templates: Vec<(PathBuf, String)>,
+    plugins: Vec<Box<SmithyPlugin + 'a>>,
+    expected_outputs: Vec<(PathBuf, Expectation)>,
+}
+
+impl<'a> Test<'a> {
+    pub fn new() -> Test<'a> {
+        Test { inputs: vec![], templates: vec![], plugins: vec![], expected_outputs: vec![] }
+    }
+
+    /// Register an input document at `path`, with its raw text (front
+    /// matter included) exactly as it would appear on disk.
+    pub fn input<P: Into<PathBuf>, B: Into<String>>(mut self, path: P, body: B) -> Self {
+        self.inputs.push((path.into(), body.into()));
+        self
+    }
+
+    /// Register a layout or partial at `path`, resolved against the
+    /// templates root rather than the loaded input tree, so it is
+    /// available to `{% include %}` and `layout:` resolution without also
+    /// being picked up as a document and emitted as an output.
+    pub fn template<P: Into<PathBuf>, B: Into<String>>(mut self, path: P, body: B) -> Self {
+        self.templates.push((path.into(), body.into()));
+        self
+    }
+
+    pub fn plugin<T: SmithyPlugin + 'a>(mut self, plugin: T) -> Self {
+        self.plugins.push(Box::new(plugin));
+        self
+    }
+
+    /// Assert the build produces an output at `path` whose body is exactly `body`.
+    pub fn expect_output<P: Into<PathBuf>, B: Into<String>>(mut self, path: P, body: B) -> Self {
+        self.expected_outputs.push((path.into(), Expectation::Exact(body.into())));
+        self
+    }
+
+    /// Assert the build produces an output at `path` whose body matches the regex `pattern`.
+    pub fn expect_output_matching<P: Into<PathBuf>, B: Into<String>>(mut self, path: P, pattern: B) -> Self {
+        self.expected_outputs.push((path.into(), Expectation::Matching(pattern.into())));
+        self
+    }
+
+    /// Write the fixtures, run the build through the registered plugins, and
+    /// assert every expected output. Panics (failing the enclosing test) on
+    /// the first mismatch or missing output.
+    pub fn run(self) {
+        let input_dir = TempDir::new("smithy-test-input").expect("create temp input dir");
+        let templates_dir = TempDir::new("smithy-test-templates").expect("create temp templates dir");
+        let output_dir = TempDir::new("smithy-test-output").expect("create temp output dir");
+
+        for (path, body) in &self.inputs {
+            let full_path = input_dir.path().join(path);
+            if let Some(parent) = full_path.parent() {
+                fs::create_dir_all(parent).expect("create input fixture directory");
+            }
+            File::create(full_path).expect("create input fixture").write(body.as_bytes()).expect("write input fixture");
+        }
+
+        for (path, body) in &self.templates {
+            let full_path = templates_dir.path().join(path);
+            if let Some(parent) = full_path.parent() {
+                fs::create_dir_all(parent).expect("create template fixture directory");
+            }
+            File::create(full_path).expect("create template fixture").write(body.as_bytes()).expect("write template fixture");
+        }
+
+        let mut smithy = Smithy {
+            output_path: output_dir.path().to_path_buf(),
+            templates_path: templates_dir.path().to_path_buf(),
+            incremental: false,
+            sources: vec![Box::new(FsLoader::new(input_dir.path()))],
+            plugins: self.plugins,
+        };
+        smithy.build().expect("smithy build");
+
+        for (path, expectation) in &self.expected_outputs {
+            let mut actual = String::new();
+            File::open(output_dir.path().join(path))
+                .unwrap_or_else(|err| panic!("expected output {:?} to exist: {}", path, err))
+                .read_to_string(&mut actual)
+                .expect("read output");
+
+            match *expectation {
+                Expectation::Exact(ref expected) => {
+                    assert_eq!(&actual, expected, "output {:?} did not match", path);
+                }
+                Expectation::Matching(ref pattern) => {
+                    let re = Regex::new(pattern).expect("valid regex pattern");
+                    assert!(re.is_match(&actual),
+                            "output {:?} (body {:?}) did not match pattern {:?}", path, actual, pattern);
+                }
+            }
+        }
+    }
+}
+
+/// Table-driven test macro for plugins: expands to a `#[test]` fn that
+/// builds a `Test` from the given fixtures, plugins, and expected outputs.
+///
+/// `outputs` asserts an exact body match; the optional `outputs_matching`
+/// asserts the body matches a regex instead. A single test can use either
+/// or both.
+///
+/// ```ignore
+/// smithy_test! {
+///     name: my_plugin_uppercases_body,
+///     inputs: { "posts/a.md" => "---\ntitle: A\n---\nbody" },
+///     plugins: [MyPlugin],
+///     outputs: { "posts/a.html" => "BODY\n" },
+///     outputs_matching: { "posts/a.meta" => r"^generated at \d+$" },
+/// }
+/// ```
+#[macro_export]
+macro_rules! smithy_test {
+    (name: $name:ident,
+     inputs: { $($input_path:expr => $input_body:expr),* $(,)* },
+     plugins: [ $($plugin:expr),* $(,)* ],
+     outputs: { $($output_path:expr => $output_body:expr),* $(,)* }
+     $(, outputs_matching: { $($pattern_path:expr => $pattern:expr),* $(,)* })*
+     $(,)*) => {
+        #[test]
+        fn $name() {
+            let mut test = $crate::testing::Test::new();
+            $( test = test.input($input_path, $input_body); )*
+            $( test = test.plugin($plugin); )*
+            $( test = test.expect_output($output_path, $output_body); )*
+            $( $( test = test.expect_output_matching($pattern_path, $pattern); )* )*
+            test.run();
+        }
+    };
+}